@@ -0,0 +1,82 @@
+//! The Linux/Android `CondvarBackend`: a single futex word.
+//!
+//! This avoids the two lock acquisitions the `generic` backend needs on its
+//! hot path: `park`/`park_timeout` wait directly on the `state` atomic
+//! `Inner` already maintains, and `unpark` wakes it with a single syscall.
+
+use super::CondvarBackend;
+use crate::loom::sync::atomic::AtomicU32;
+
+use std::sync::atomic::Ordering::SeqCst;
+use std::time::{Duration, Instant};
+
+pub(super) struct Futex;
+
+impl CondvarBackend for Futex {
+    fn new() -> Futex {
+        Futex
+    }
+
+    fn park(&self, state: &AtomicU32, expected: u32) {
+        while state.load(SeqCst) == expected {
+            futex_wait(state, expected, std::ptr::null());
+        }
+    }
+
+    fn park_timeout(&self, state: &AtomicU32, expected: u32, timeout: Duration) {
+        let start = Instant::now();
+        let mut remaining = timeout;
+
+        while state.load(SeqCst) == expected {
+            let ts = libc::timespec {
+                tv_sec: remaining.as_secs() as libc::time_t,
+                tv_nsec: remaining.subsec_nanos() as libc::c_long,
+            };
+            futex_wait(state, expected, &ts);
+
+            let elapsed = start.elapsed();
+            if elapsed >= timeout {
+                break;
+            }
+
+            remaining = timeout - elapsed;
+        }
+    }
+
+    fn unpark(&self, state: &AtomicU32) {
+        // SAFETY: the syscall only reads `state`'s address; it does not
+        // retain it past the call.
+        unsafe {
+            libc::syscall(
+                libc::SYS_futex,
+                state as *const AtomicU32,
+                libc::FUTEX_WAKE | libc::FUTEX_PRIVATE_FLAG,
+                1,
+            );
+        }
+    }
+}
+
+/// Blocks the current thread while `state` holds `expected`, or until
+/// `timeout` (`NULL` for no timeout) elapses. Returns on a spurious wakeup,
+/// a real notification, or timeout; the caller is expected to re-check
+/// `state` either way.
+fn futex_wait(state: &AtomicU32, expected: u32, timeout: *const libc::timespec) {
+    // SAFETY: the syscall only reads `state`'s address, `expected`, and
+    // `timeout`; it does not retain any of them past the call.
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            state as *const AtomicU32,
+            libc::FUTEX_WAIT | libc::FUTEX_PRIVATE_FLAG,
+            expected,
+            timeout,
+        );
+    }
+}
+
+// Drives real OS threads with wall-clock sleeps against the loom-instrumented
+// `AtomicU32` above, which loom's simulation can't be driven through outside
+// of `loom::model`; see `backend_condvar_tests!`.
+#[cfg(all(test, not(loom)))]
+super::backend_condvar_tests!(Futex);
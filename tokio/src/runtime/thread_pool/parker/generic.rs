@@ -0,0 +1,79 @@
+//! The portable `CondvarBackend`: a `Mutex`/`Condvar` pair.
+//!
+//! Used on every target that doesn't have a more specific backend (see
+//! `futex` for the Linux/Android one).
+
+use super::CondvarBackend;
+use crate::loom::sync::atomic::AtomicU32;
+use crate::loom::sync::{Condvar, Mutex};
+
+use std::sync::atomic::Ordering::SeqCst;
+use std::time::{Duration, Instant};
+
+pub(super) struct Generic {
+    /// Used to coordinate access to the condvar.
+    mutex: Mutex<()>,
+
+    /// Condvar to block on.
+    condvar: Condvar,
+}
+
+impl CondvarBackend for Generic {
+    fn new() -> Generic {
+        Generic {
+            mutex: Mutex::new(()),
+            condvar: Condvar::new(),
+        }
+    }
+
+    fn park(&self, state: &AtomicU32, expected: u32) {
+        let mut m = self.mutex.lock();
+
+        while state.load(SeqCst) == expected {
+            m = self.condvar.wait(m).unwrap();
+        }
+    }
+
+    fn park_timeout(&self, state: &AtomicU32, expected: u32, timeout: Duration) {
+        let mut m = self.mutex.lock();
+
+        let start = Instant::now();
+        let mut remaining = timeout;
+
+        while state.load(SeqCst) == expected {
+            let (guard, result) = self.condvar.wait_timeout(m, remaining).unwrap();
+            m = guard;
+
+            if result.timed_out() {
+                break;
+            }
+
+            // Spurious wakeup; don't let a string of them reset the deadline.
+            let elapsed = start.elapsed();
+            if elapsed >= timeout {
+                break;
+            }
+
+            remaining = timeout - elapsed;
+        }
+    }
+
+    fn unpark(&self, _state: &AtomicU32) {
+        // There is a period between when the parked thread observes `state`
+        // still holding `expected` and when it actually waits on the condvar.
+        // If we were to notify during this period it would be ignored, and
+        // the parked thread would then go to sleep having missed it.
+        // Acquiring `mutex` here waits out that period, since the parked
+        // thread holds it until it calls `Condvar::wait`, which atomically
+        // releases it for the duration of the wait.
+        drop(self.mutex.lock());
+
+        self.condvar.notify_one();
+    }
+}
+
+// Drives real OS threads with wall-clock sleeps against the loom-instrumented
+// `Mutex`/`Condvar`/`AtomicU32` above, which loom's simulation can't be driven
+// through outside of `loom::model`; see `backend_condvar_tests!`.
+#[cfg(all(test, not(loom)))]
+super::backend_condvar_tests!(Generic);